@@ -1,21 +1,42 @@
 //! Consensus manager, see Manager struct.
+//!
+//! This file depends on companion changes in `crate::types::ConsensusContext` (an added/extended
+//! `validators` returning per-validator voting power, plus new `gossip_commit_certificate` and
+//! `fetch_and_verify_block` methods) and in `crate::single_height_consensus::SingleHeightConsensus`
+//! (its constructor gaining a `wal: ConsensusWal` parameter). Those modules live outside this
+//! source tree and are not part of this change; this series does not compile, and should not be
+//! merged, until they land alongside it.
+//!
+//! In particular, `ConsensusWal::append` is only ever called by this file's tests today
+//! (`persist_commit_certificate` goes through `store_commit_certificate` instead, which does not
+//! append a vote entry); `SingleHeightConsensus`, where a proposal,
+//! prevote, or precommit is actually signed, is the component that needs to call `append` before
+//! each one is broadcast, and that call site is part of the not-yet-landed companion change, not
+//! this file. Until it lands, no real vote is write-ahead logged.
 
 #[cfg(test)]
 #[path = "manager_test.rs"]
 mod manager_test;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use std::time::Duration;
 
 use futures::channel::{mpsc, oneshot};
 use futures::stream::FuturesUnordered;
 use futures::{Stream, StreamExt};
-use papyrus_common::metrics::{PAPYRUS_CONSENSUS_HEIGHT, PAPYRUS_CONSENSUS_SYNC_COUNT};
+use papyrus_common::metrics::{
+    PAPYRUS_CONSENSUS_HEIGHT,
+    PAPYRUS_CONSENSUS_NUM_CONNECTED_PEERS,
+    PAPYRUS_CONSENSUS_SYNC_COUNT,
+};
 use papyrus_network::network_manager::ReportSender;
-use papyrus_protobuf::consensus::{ConsensusMessage, Proposal};
+use papyrus_protobuf::consensus::{ConsensusMessage, Proposal, Vote, VoteType};
 use papyrus_protobuf::converters::ProtobufConversionError;
+use papyrus_storage::{StorageReader, StorageWriter};
 use starknet_api::block::{BlockHash, BlockNumber};
-use tracing::{debug, info, instrument};
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, info, instrument, warn};
 
 use crate::config::TimeoutsConfig;
 use crate::single_height_consensus::{ShcReturn, ShcTask, SingleHeightConsensus};
@@ -31,36 +52,41 @@ use crate::types::{
 // TODO(dvir): add test for this.
 #[instrument(skip_all, level = "info")]
 #[allow(missing_docs)]
-pub async fn run_consensus<BlockT, ContextT, NetworkReceiverT, SyncReceiverT>(
+pub async fn run_consensus<BlockT, ContextT, NetworkReceiverT, SyncReceiverT, NumPeersReceiverT>(
     mut context: ContextT,
     start_height: BlockNumber,
     validator_id: ValidatorId,
-    consensus_delay: Duration,
+    readiness_config: PeerReadinessConfig,
     timeouts: TimeoutsConfig,
+    cache_config: MessageCacheConfig,
+    sync_config: SyncConfig,
+    wal: ConsensusWal,
     mut network_receiver: NetworkReceiverT,
     mut sync_receiver: SyncReceiverT,
+    mut num_connected_peers: NumPeersReceiverT,
+    readiness_sender: watch::Sender<bool>,
 ) -> Result<(), ConsensusError>
 where
     BlockT: ConsensusBlock,
-    ContextT: ConsensusContext<Block = BlockT>,
+    ContextT: ConsensusContext<Block = BlockT> + Clone,
     NetworkReceiverT:
         Stream<Item = (Result<ConsensusMessage, ProtobufConversionError>, ReportSender)> + Unpin,
-    SyncReceiverT: Stream<Item = BlockNumber> + Unpin,
+    SyncReceiverT: Stream<Item = CommitCertificate> + Unpin,
+    NumPeersReceiverT: Stream<Item = usize> + Unpin,
     ProposalWrapper:
         Into<(ProposalInit, mpsc::Receiver<BlockT::ProposalChunk>, oneshot::Receiver<BlockHash>)>,
 {
     info!(
-        "Running consensus, start_height={}, validator_id={}, consensus_delay={}, timeouts={:?}",
-        start_height,
-        validator_id,
-        consensus_delay.as_secs(),
-        timeouts
+        "Running consensus, start_height={}, validator_id={}, readiness_config={:?}, \
+         timeouts={:?}, sync_config={:?}",
+        start_height, validator_id, readiness_config, timeouts, sync_config
     );
 
-    // Add a short delay to allow peers to connect and avoid "InsufficientPeers" error
-    tokio::time::sleep(consensus_delay).await;
+    // Rather than a blind delay "to allow peers to connect", wait until we've continuously held
+    // at least `min_connected_peers` for `time_until_ready` before proposing/voting for a height.
+    wait_until_ready(&readiness_config, &mut num_connected_peers, &readiness_sender).await?;
     let mut current_height = start_height;
-    let mut manager = MultiHeightManager::new(validator_id, timeouts);
+    let mut manager = MultiHeightManager::new(validator_id, timeouts, cache_config, wal);
     loop {
         metrics::gauge!(PAPYRUS_CONSENSUS_HEIGHT, current_height.0 as f64);
 
@@ -73,12 +99,54 @@ where
         tokio::select! {
             decision = run_height => {
                 let decision = decision?;
+                // Package the quorum of precommits into a certificate before handing the decision
+                // off, so a peer syncing this height can later verify finality without replaying
+                // the round. It's always persisted, but only gossiped at heights that are a
+                // multiple of `checkpoint_period`, to avoid flooding the network every height.
+                let certificate = build_commit_certificate(
+                    current_height,
+                    decision.block.id(),
+                    &decision.precommits,
+                );
+                manager.persist_commit_certificate(&certificate).await?;
+                if current_height.0 % sync_config.checkpoint_period == 0 {
+                    context.gossip_commit_certificate(certificate).await?;
+                }
                 context.decision_reached(decision.block, decision.precommits).await?;
                 current_height = current_height.unchecked_next();
             },
             sync_height = sync_height(current_height, &mut sync_receiver) => {
+                // The certificate's quorum is verified here, after `run_height`'s borrow of
+                // `context` has been dropped by `select!`, rather than inside `sync_height`
+                // itself, so the two branches don't need to borrow `context` concurrently.
+                let certificate = sync_height?;
+                // Certificates arrive over the network, so a bad one (malformed, adversarial, or
+                // simply stale) must not take the whole consensus task down with it; log and wait
+                // for the next one instead of propagating the error out of this function.
+                if let Err(e) = verify_commit_certificate(&certificate, &mut context).await {
+                    warn!("Ignoring invalid commit certificate for height {}: {:?}", certificate.height, e);
+                    continue;
+                }
+                let target_height = certificate.height;
+                // Below the threshold we trust that the skipped blocks were obtained elsewhere
+                // (e.g. by another node already having run consensus for them); beyond it we
+                // reconstruct their decisions ourselves rather than silently jumping the height.
+                if target_height.0 > current_height.0 + sync_config.threshold {
+                    catch_up(&mut context, current_height, target_height, &sync_config).await?;
+                }
                 metrics::increment_counter!(PAPYRUS_CONSENSUS_SYNC_COUNT);
-                current_height = sync_height?.unchecked_next();
+                current_height = target_height.unchecked_next();
+            },
+            // Keep observing the peer count after startup, rather than freezing the watch channel
+            // and metric at whatever they were when `wait_until_ready` first returned. This arm
+            // never itself blocks leaving the height (it doesn't touch `context`), so it's always
+            // safe to select alongside `run_height`.
+            count = num_connected_peers.next() => {
+                let count = count.ok_or_else(|| ConsensusError::InternalNetworkError(
+                    "Connected peers stream should never be closed".to_string(),
+                ))?;
+                metrics::gauge!(PAPYRUS_CONSENSUS_NUM_CONNECTED_PEERS, count as f64);
+                let _ = readiness_sender.send(count >= readiness_config.min_connected_peers);
             }
         }
     }
@@ -89,19 +157,358 @@ where
 #[allow(missing_docs)]
 pub struct ProposalWrapper(pub Proposal);
 
+/// Gates when consensus starts proposing/voting on readiness of the peer-to-peer network, in
+/// place of a fixed blind delay. We don't declare ourselves ready the instant
+/// `min_connected_peers` is crossed (a single flaky peer could flap us in and out of readiness),
+/// but we also don't want a fixed delay that either proposes too early with too few peers or
+/// wastes wall-clock time peers didn't need to connect.
+#[derive(Debug, Clone)]
+pub struct PeerReadinessConfig {
+    /// Minimum number of connected peers that must be continuously held before consensus starts.
+    pub min_connected_peers: usize,
+    /// How long `min_connected_peers` must be continuously satisfied before consensus starts.
+    pub time_until_ready: Duration,
+}
+
+impl Default for PeerReadinessConfig {
+    fn default() -> Self {
+        Self { min_connected_peers: 1, time_until_ready: Duration::from_secs(5) }
+    }
+}
+
+/// Waits until `min_connected_peers` has been continuously held for `time_until_ready`,
+/// resetting the debounce timer whenever the peer count drops below the threshold in the
+/// meantime. Publishes the readiness state on `readiness_sender` so other components (e.g.
+/// metrics, RPC health) can observe it.
+async fn wait_until_ready<NumPeersReceiverT>(
+    config: &PeerReadinessConfig,
+    num_connected_peers: &mut NumPeersReceiverT,
+    readiness_sender: &watch::Sender<bool>,
+) -> Result<(), ConsensusError>
+where
+    NumPeersReceiverT: Stream<Item = usize> + Unpin,
+{
+    let closed_stream_err = || {
+        ConsensusError::InternalNetworkError(
+            "Connected peers stream should never be closed".to_string(),
+        )
+    };
+    loop {
+        // Wait until we observe enough peers to start the debounce timer.
+        loop {
+            let count = num_connected_peers.next().await.ok_or_else(closed_stream_err)?;
+            metrics::gauge!(PAPYRUS_CONSENSUS_NUM_CONNECTED_PEERS, count as f64);
+            if count >= config.min_connected_peers {
+                break;
+            }
+        }
+
+        let debounce = tokio::time::sleep(config.time_until_ready);
+        tokio::pin!(debounce);
+        let mut dropped_below_threshold = false;
+        loop {
+            tokio::select! {
+                () = &mut debounce => break,
+                count = num_connected_peers.next() => {
+                    let count = count.ok_or_else(closed_stream_err)?;
+                    metrics::gauge!(PAPYRUS_CONSENSUS_NUM_CONNECTED_PEERS, count as f64);
+                    if count < config.min_connected_peers {
+                        dropped_below_threshold = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if !dropped_below_threshold {
+            info!("Peer readiness threshold held for {:?}, consensus is ready.", config.time_until_ready);
+            let _ = readiness_sender.send(true);
+            return Ok(());
+        }
+        debug!("Connected peer count dropped below threshold, resetting readiness debounce.");
+    }
+}
+
+/// The step of Tendermint's round a signed action was cast for. Together with `(height, round)`
+/// this identifies the slot the no-equivocation invariant is enforced over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConsensusStep {
+    /// The proposal for the round.
+    Proposal,
+    /// A prevote.
+    Prevote,
+    /// A precommit.
+    Precommit,
+}
+
+/// A single write-ahead-log entry: a signed action taken (or about to be taken) for a given
+/// `(height, round, step)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalEntry {
+    /// The height the action was cast for.
+    pub height: u64,
+    /// The round, within `height`, the action was cast for.
+    pub round: u32,
+    /// The step of the round the action belongs to.
+    pub step: ConsensusStep,
+    /// The value signed, or `None` for a nil vote.
+    pub value: Option<BlockHash>,
+}
+
+/// Write-ahead log of signed consensus actions, persisted via `papyrus_storage` so the node can
+/// recover an in-progress height after a crash without risking equivocation.
+///
+/// Invariant: at most one distinct signed value is recorded per `(height, round, step)`. A
+/// `SingleHeightConsensus` built with a `ConsensusWal` replays the log for its height on
+/// construction and refuses to sign anything else for a `(height, round, step)` it already has
+/// an entry for.
+#[derive(Clone)]
+pub struct ConsensusWal {
+    storage_reader: StorageReader,
+    storage_writer: Arc<Mutex<StorageWriter>>,
+}
+
+impl std::fmt::Debug for ConsensusWal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConsensusWal").finish_non_exhaustive()
+    }
+}
+
+impl ConsensusWal {
+    /// Creates a new write-ahead log backed by the node's storage.
+    pub fn new(storage_reader: StorageReader, storage_writer: StorageWriter) -> Self {
+        Self { storage_reader, storage_writer: Arc::new(Mutex::new(storage_writer)) }
+    }
+
+    /// Records `entry`. Must be called before the corresponding action is broadcast. Returns an
+    /// error if a different value was already recorded for the same `(height, round, step)`.
+    pub async fn append(&self, entry: WalEntry) -> Result<(), ConsensusError> {
+        for existing in self.entries_for_height(entry.height)? {
+            if existing.round == entry.round
+                && existing.step == entry.step
+                && existing.value != entry.value
+            {
+                return Err(ConsensusError::InternalNetworkError(format!(
+                    "Refusing to sign conflicting value for height={} round={} step={:?}: \
+                     already signed {:?}, now attempting {:?}",
+                    entry.height, entry.round, entry.step, existing.value, entry.value
+                )));
+            }
+        }
+        self.storage_writer
+            .lock()
+            .await
+            .begin_rw_txn()
+            .and_then(|txn| txn.append_consensus_wal_entry(&entry))
+            .and_then(|txn| txn.commit())
+            .map_err(|e| ConsensusError::InternalNetworkError(e.to_string()))
+    }
+
+    /// Returns all entries recorded for `height`, in the order they were appended.
+    pub fn entries_for_height(&self, height: u64) -> Result<Vec<WalEntry>, ConsensusError> {
+        self.storage_reader
+            .begin_ro_txn()
+            .and_then(|txn| txn.get_consensus_wal_entries(height))
+            .map_err(|e| ConsensusError::InternalNetworkError(e.to_string()))
+    }
+
+    /// Drops all entries below `height`; once a height is committed, earlier entries can no
+    /// longer affect equivocation safety.
+    pub async fn prune_below(&self, height: u64) -> Result<(), ConsensusError> {
+        self.storage_writer
+            .lock()
+            .await
+            .begin_rw_txn()
+            .and_then(|txn| txn.prune_consensus_wal_below(height))
+            .and_then(|txn| txn.commit())
+            .map_err(|e| ConsensusError::InternalNetworkError(e.to_string()))
+    }
+
+    /// Persists a commit certificate alongside the write-ahead log, so a restarted node (or a
+    /// peer later asking for it during sync) can retrieve the finality proof for a height.
+    pub async fn store_commit_certificate(
+        &self,
+        certificate: &CommitCertificate,
+    ) -> Result<(), ConsensusError> {
+        self.storage_writer
+            .lock()
+            .await
+            .begin_rw_txn()
+            .and_then(|txn| txn.append_commit_certificate(certificate))
+            .and_then(|txn| txn.commit())
+            .map_err(|e| ConsensusError::InternalNetworkError(e.to_string()))
+    }
+
+    /// Returns the persisted commit certificate for `height`, if any.
+    pub fn get_commit_certificate(
+        &self,
+        height: u64,
+    ) -> Result<Option<CommitCertificate>, ConsensusError> {
+        self.storage_reader
+            .begin_ro_txn()
+            .and_then(|txn| txn.get_commit_certificate(height))
+            .map_err(|e| ConsensusError::InternalNetworkError(e.to_string()))
+    }
+}
+
+/// A validator together with its voting power, so quorum and proposer selection can be weighted
+/// by stake instead of assuming one vote per validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightedValidator {
+    /// The validator's identity.
+    pub id: ValidatorId,
+    /// The validator's voting power for this height.
+    pub voting_power: u64,
+}
+
+/// The validator set for a height, together with each validator's voting power. Quorum is more
+/// than 2/3 of total voting power (rather than more than 2/3 of the validator count), and
+/// proposer selection is a deterministic weighted round-robin: each validator's priority
+/// increments by its power every round, the highest-priority validator proposes, then has the
+/// total power subtracted from its priority.
+#[derive(Debug, Clone)]
+pub struct ValidatorSet {
+    validators: Vec<WeightedValidator>,
+    total_power: u64,
+    // Priorities as of the last round computed by `proposer_for_round`, so repeated calls with
+    // monotonically increasing rounds (the common case: a height stalling through successive
+    // timeouts) can resume from there instead of replaying from round 0 every time. Interior
+    // mutability keeps `proposer_for_round` a `&self` method, since it's conceptually a pure
+    // query over the (immutable) validator set.
+    priority_cache: std::cell::RefCell<Option<ProposerPriorityCache>>,
+}
+
+#[derive(Debug, Clone)]
+struct ProposerPriorityCache {
+    round: u32,
+    priorities: Vec<i128>,
+    proposer_index: usize,
+}
+
+impl ValidatorSet {
+    /// Creates a new validator set from `validators`.
+    pub fn new(validators: Vec<WeightedValidator>) -> Self {
+        let total_power = validators.iter().map(|v| v.voting_power).sum();
+        Self { validators, total_power, priority_cache: std::cell::RefCell::new(None) }
+    }
+
+    /// The validators in this set.
+    pub fn validators(&self) -> &[WeightedValidator] {
+        &self.validators
+    }
+
+    /// The total voting power across all validators in the set.
+    pub fn total_power(&self) -> u64 {
+        self.total_power
+    }
+
+    /// The minimum voting power required for a quorum: strictly more than 2/3 of total power.
+    pub fn quorum_threshold(&self) -> u64 {
+        (2 * self.total_power) / 3 + 1
+    }
+
+    /// Whether `power` reaches a quorum of this validator set.
+    pub fn has_quorum(&self, power: u64) -> bool {
+        power >= self.quorum_threshold()
+    }
+
+    /// Returns the proposer for `round`, applying the accumulated-priority scheme from round `0`
+    /// up to and including `round`. Caches the priorities as of the highest round computed so
+    /// far: a query for a round at or after that resumes from the cache instead of replaying
+    /// from genesis, so repeated queries for a stalled height (ever-increasing rounds) stay
+    /// linear in the number of new rounds rather than quadratic in the round number. A query for
+    /// an earlier round (e.g. verifying a certificate from a past round) still replays from
+    /// scratch, since priorities aren't reversible.
+    pub fn proposer_for_round(&self, round: u32) -> ValidatorId {
+        let mut cache = self.priority_cache.borrow_mut();
+        let (mut priorities, mut proposer_index, next_round) = match cache.take() {
+            Some(cached) if cached.round == round => {
+                let proposer_id = self.validators[cached.proposer_index].id;
+                *cache = Some(cached);
+                return proposer_id;
+            }
+            Some(cached) if cached.round < round => {
+                (cached.priorities, cached.proposer_index, cached.round + 1)
+            }
+            _ => (vec![0_i128; self.validators.len()], 0, 0),
+        };
+        for _ in next_round..=round {
+            for (validator, priority) in self.validators.iter().zip(priorities.iter_mut()) {
+                *priority += i128::from(validator.voting_power);
+            }
+            proposer_index = priorities
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, priority)| **priority)
+                .map(|(index, _)| index)
+                .expect("validator set must not be empty");
+            priorities[proposer_index] -= i128::from(self.total_power);
+        }
+        *cache = Some(ProposerPriorityCache { round, priorities, proposer_index });
+        self.validators[proposer_index].id
+    }
+}
+
+/// Bounds on how far ahead, and how much, of future-height consensus messages the
+/// [`MultiHeightManager`] is willing to buffer before the corresponding height is reached.
+///
+/// Without these bounds a single malicious (or simply fast) peer could flood the manager with
+/// messages for heights far in the future, exhausting memory long before those heights are ever
+/// reached.
+#[derive(Debug, Clone)]
+pub struct MessageCacheConfig {
+    /// Only cache messages for heights in `[height + 1, height + lookahead]`; anything further
+    /// ahead is dropped.
+    pub lookahead: u64,
+    /// Maximum number of messages cached across all future heights combined.
+    pub cache_capacity: usize,
+    /// Maximum number of messages cached on behalf of a single sender.
+    pub sender_quota: usize,
+}
+
+impl Default for MessageCacheConfig {
+    fn default() -> Self {
+        Self { lookahead: 10, cache_capacity: 1000, sender_quota: 50 }
+    }
+}
+
 /// Runs Tendermint repeatedly across different heights. Handles issues which are not explicitly
 /// part of the single height consensus algorithm (e.g. messages from future heights).
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct MultiHeightManager {
     validator_id: ValidatorId,
     cached_messages: BTreeMap<u64, Vec<ConsensusMessage>>,
+    cached_messages_len: usize,
+    cached_messages_per_sender: HashMap<ValidatorId, usize>,
     timeouts: TimeoutsConfig,
+    cache_config: MessageCacheConfig,
+    wal: ConsensusWal,
 }
 
 impl MultiHeightManager {
     /// Create a new consensus manager.
-    pub fn new(validator_id: ValidatorId, timeouts: TimeoutsConfig) -> Self {
-        Self { validator_id, cached_messages: BTreeMap::new(), timeouts }
+    pub fn new(
+        validator_id: ValidatorId,
+        timeouts: TimeoutsConfig,
+        cache_config: MessageCacheConfig,
+        wal: ConsensusWal,
+    ) -> Self {
+        Self {
+            validator_id,
+            cached_messages: BTreeMap::new(),
+            cached_messages_len: 0,
+            cached_messages_per_sender: HashMap::new(),
+            timeouts,
+            cache_config,
+            wal,
+        }
+    }
+
+    /// Persists `certificate` via the manager's write-ahead log storage.
+    pub async fn persist_commit_certificate(
+        &self,
+        certificate: &CommitCertificate,
+    ) -> Result<(), ConsensusError> {
+        self.wal.store_commit_certificate(certificate).await
     }
 
     /// Run the consensus algorithm for a single height.
@@ -126,12 +533,16 @@ impl MultiHeightManager {
             oneshot::Receiver<BlockHash>,
         )>,
     {
-        let validators = context.validators(height).await;
+        // Replaying the WAL for `height` into the freshly constructed `SingleHeightConsensus` lets
+        // it refuse to sign a conflicting value at any `(height, round, step)` it already acted on
+        // before a crash or restart, so re-entering a height is safe from equivocation.
+        let validators = ValidatorSet::new(context.validators(height).await);
         let mut shc = SingleHeightConsensus::new(
             height,
             self.validator_id,
             validators,
             self.timeouts.clone(),
+            self.wal.clone(),
         );
         let mut shc_tasks = FuturesUnordered::new();
 
@@ -144,11 +555,12 @@ impl MultiHeightManager {
             }
         }
 
-        let mut current_height_messages = self.get_current_height_messages(height);
+        let mut current_height_messages = self.get_current_height_messages(height).await;
         loop {
             let shc_return = tokio::select! {
                 message = next_message(&mut current_height_messages, network_receiver) => {
-                    self.handle_message(context, height, &mut shc, message?).await?
+                    let (message, report_sender) = message?;
+                    self.handle_message(context, height, &mut shc, message, report_sender).await?
                 },
                 Some(shc_task) = shc_tasks.next() => {
                     shc.handle_task(context, shc_task).await?
@@ -173,6 +585,7 @@ impl MultiHeightManager {
         height: BlockNumber,
         shc: &mut SingleHeightConsensus<BlockT>,
         message: ConsensusMessage,
+        report_sender: Option<ReportSender>,
     ) -> Result<ShcReturn<BlockT>, ConsensusError>
     where
         BlockT: ConsensusBlock,
@@ -183,14 +596,10 @@ impl MultiHeightManager {
             oneshot::Receiver<BlockHash>,
         )>,
     {
-        // TODO(matan): We need to figure out an actual cacheing strategy under 2 constraints:
-        // 1. Malicious - must be capped so a malicious peer can't DoS us.
-        // 2. Parallel proposals - we may send/receive a proposal for (H+1, 0).
-        // In general I think we will want to only cache (H+1, 0) messages.
         if message.height() != height.0 {
             debug!("Received a message for a different height. {:?}", message);
             if message.height() > height.0 {
-                self.cached_messages.entry(message.height()).or_default().push(message);
+                self.cache_message(height, message, report_sender);
             }
             return Ok(ShcReturn::Tasks(vec![]));
         }
@@ -211,11 +620,58 @@ impl MultiHeightManager {
         }
     }
 
+    // Caches a message for a future height, subject to the lookahead window, the global cache
+    // capacity and the per-sender quota. Messages dropped for exceeding a quota cause the
+    // sender to be reported so the network layer can penalize it.
+    fn cache_message(
+        &mut self,
+        height: BlockNumber,
+        message: ConsensusMessage,
+        report_sender: Option<ReportSender>,
+    ) {
+        let msg_height = message.height();
+        if msg_height > height.0 + self.cache_config.lookahead {
+            debug!("Dropping message beyond the lookahead window: {:?}", message);
+            report_sender_misbehavior(report_sender);
+            return;
+        }
+
+        let sender = message_sender(&message);
+        // Look up the count without inserting: `sender` comes straight off the (potentially
+        // adversarial) message, so inserting a zero entry here before the capacity check passes
+        // would let an attacker grow `cached_messages_per_sender` unboundedly by varying it, once
+        // the global cache is already full and every message is dropped on the capacity check
+        // below before ever being counted against its sender's quota.
+        let sender_count = self.cached_messages_per_sender.get(&sender).copied().unwrap_or(0);
+        if self.cached_messages_len >= self.cache_config.cache_capacity
+            || sender_count >= self.cache_config.sender_quota
+        {
+            debug!(
+                "Dropping cached message, cache or sender quota exceeded. sender={:?}",
+                sender
+            );
+            report_sender_misbehavior(report_sender);
+            return;
+        }
+
+        *self.cached_messages_per_sender.entry(sender).or_insert(0) += 1;
+        self.cached_messages_len += 1;
+        self.cached_messages.entry(msg_height).or_default().push(message);
+    }
+
     // Filters the cached messages:
     // - returns all of the current height messages.
     // - drops messages from earlier heights.
     // - retains future messages in the cache.
-    fn get_current_height_messages(&mut self, height: BlockNumber) -> Vec<ConsensusMessage> {
+    // Also the natural point to garbage-collect WAL entries below the height we're entering,
+    // since they can no longer affect equivocation safety once it's committed.
+    async fn get_current_height_messages(&mut self, height: BlockNumber) -> Vec<ConsensusMessage> {
+        if let Err(e) = self.wal.prune_below(height.0).await {
+            // A storage write failure here leaves stale WAL entries accumulating with no other
+            // visible signal, so this is a persistence fault worth a `warn!`, not routine `debug!`
+            // traffic.
+            warn!("Failed to prune consensus WAL below height {}: {:?}", height, e);
+        }
         // Depends on `cached_messages` being sorted by height.
         loop {
             let Some(entry) = self.cached_messages.first_entry() else {
@@ -223,33 +679,69 @@ impl MultiHeightManager {
             };
             match entry.key().cmp(&height.0) {
                 std::cmp::Ordering::Greater => return Vec::new(),
-                std::cmp::Ordering::Equal => return entry.remove(),
+                std::cmp::Ordering::Equal => {
+                    let messages = entry.remove();
+                    self.release_cache_slots(&messages);
+                    return messages;
+                }
                 std::cmp::Ordering::Less => {
-                    entry.remove();
+                    let messages = entry.remove();
+                    self.release_cache_slots(&messages);
+                }
+            }
+        }
+    }
+
+    // Frees the bookkeeping (global count and per-sender quota) held by messages leaving the
+    // cache, whether because they're about to be processed or because they expired.
+    fn release_cache_slots(&mut self, messages: &[ConsensusMessage]) {
+        for message in messages {
+            self.cached_messages_len -= 1;
+            let sender = message_sender(message);
+            if let Some(count) = self.cached_messages_per_sender.get_mut(&sender) {
+                *count -= 1;
+                if *count == 0 {
+                    self.cached_messages_per_sender.remove(&sender);
                 }
             }
         }
     }
 }
 
+// Returns the `ValidatorId` responsible for sending this message, used for per-sender cache
+// quotas and reporting.
+fn message_sender(message: &ConsensusMessage) -> ValidatorId {
+    match message {
+        ConsensusMessage::Proposal(proposal) => proposal.proposer,
+        ConsensusMessage::Vote(vote) => vote.voter,
+    }
+}
+
+// Best-effort report of a misbehaving sender; we don't treat a closed report channel as fatal.
+fn report_sender_misbehavior(report_sender: Option<ReportSender>) {
+    if let Some(report_sender) = report_sender {
+        let _ = report_sender.send(());
+    }
+}
+
 async fn next_message<NetworkReceiverT>(
     cached_messages: &mut Vec<ConsensusMessage>,
     network_receiver: &mut NetworkReceiverT,
-) -> Result<ConsensusMessage, ConsensusError>
+) -> Result<(ConsensusMessage, Option<ReportSender>), ConsensusError>
 where
     NetworkReceiverT:
         Stream<Item = (Result<ConsensusMessage, ProtobufConversionError>, ReportSender)> + Unpin,
 {
     if let Some(msg) = cached_messages.pop() {
-        return Ok(msg);
+        // Already reported (if applicable) when it was first received and cached.
+        return Ok((msg, None));
     }
 
     let (msg, report_sender) = network_receiver.next().await.ok_or_else(|| {
         ConsensusError::InternalNetworkError("NetworkReceiver should never be closed".to_string())
     })?;
     match msg {
-        // TODO(matan): Return report_sender for use in later errors by SHC.
-        Ok(msg) => Ok(msg),
+        Ok(msg) => Ok((msg, Some(report_sender))),
         Err(e) => {
             // Failed to parse consensus message
             report_sender.send(()).or(Err(ConsensusError::InternalNetworkError(
@@ -260,22 +752,27 @@ where
     }
 }
 
-// Return only when a height is reached that is greater than or equal to the current height.
+// Return only when a certificate for a height reached that is greater than or equal to the
+// current height. Quorum verification is left to the caller (see the comment at the `sync_height`
+// call site in `run_consensus`), since it needs `context` which may still be borrowed here.
 async fn sync_height<SyncReceiverT>(
     height: BlockNumber,
     mut sync_receiver: SyncReceiverT,
-) -> Result<BlockNumber, ConsensusError>
+) -> Result<CommitCertificate, ConsensusError>
 where
-    SyncReceiverT: Stream<Item = BlockNumber> + Unpin,
+    SyncReceiverT: Stream<Item = CommitCertificate> + Unpin,
 {
     loop {
         match sync_receiver.next().await {
-            Some(sync_height) if sync_height >= height => {
-                info!("Sync to height: {}. current_height={}", sync_height, height);
-                return Ok(sync_height);
+            Some(certificate) if certificate.height >= height => {
+                info!("Sync to height: {}. current_height={}", certificate.height, height);
+                return Ok(certificate);
             }
-            Some(sync_height) => {
-                debug!("Ignoring sync to height: {}. current_height={}", sync_height, height);
+            Some(certificate) => {
+                debug!(
+                    "Ignoring certificate for height: {}. current_height={}",
+                    certificate.height, height
+                );
             }
             None => {
                 return Err(ConsensusError::SyncError("Sync receiver closed".to_string()));
@@ -284,6 +781,195 @@ where
     }
 }
 
+/// Configuration for the catch-up (fast-sync) block download pipeline used once the node has
+/// fallen more than `threshold` heights behind.
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Only engage the catch-up pipeline once we're more than this many heights behind; below
+    /// it we trust that the skipped blocks were obtained (and their decisions reconstructed)
+    /// elsewhere.
+    pub threshold: u64,
+    /// Maximum number of blocks fetched, verified or queued for in-order commit at once.
+    pub lookahead: usize,
+    /// Per-block fetch-and-verify timeout before the block is retried.
+    pub fetch_timeout: Duration,
+    /// A commit certificate is gossiped at least this often (in heights), so newly joined nodes
+    /// have fixed checkpoints to anchor to even while otherwise following the chain live.
+    pub checkpoint_period: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            lookahead: 16,
+            fetch_timeout: Duration::from_secs(10),
+            checkpoint_period: 100,
+        }
+    }
+}
+
+/// A self-contained, verifiable proof that a height was finalized: the decided block hash,
+/// height, round, and the precommit signatures that constituted the >2/3 quorum. Lets a peer
+/// syncing this height verify finality without replaying the full round.
+#[derive(Debug, Clone)]
+pub struct CommitCertificate {
+    /// The finalized height.
+    pub height: BlockNumber,
+    /// The round at which quorum on `block_hash` was reached.
+    pub round: u32,
+    /// The decided block.
+    pub block_hash: BlockHash,
+    /// The precommit votes constituting the quorum.
+    pub precommits: Vec<Vote>,
+}
+
+// Packages a decided height's quorum of precommits into a `CommitCertificate`.
+fn build_commit_certificate(
+    height: BlockNumber,
+    block_hash: BlockHash,
+    precommits: &[Vote],
+) -> CommitCertificate {
+    let round = precommits.first().map_or(0, |vote| vote.round);
+    CommitCertificate { height, round, block_hash, precommits: precommits.to_vec() }
+}
+
+// Verifies that a `CommitCertificate`'s precommits, from distinct validators of its height's
+// validator set and matching its `(height, round, block_hash)`, reach a voting-power quorum.
+async fn verify_commit_certificate<BlockT, ContextT>(
+    certificate: &CommitCertificate,
+    context: &mut ContextT,
+) -> Result<(), ConsensusError>
+where
+    BlockT: ConsensusBlock,
+    ContextT: ConsensusContext<Block = BlockT>,
+{
+    let validators = ValidatorSet::new(context.validators(certificate.height).await);
+    let power = certificate_voting_power(certificate, &validators);
+    if !validators.has_quorum(power) {
+        return Err(ConsensusError::SyncError(format!(
+            "Commit certificate for height {} does not reach quorum: {} / {} required",
+            certificate.height,
+            power,
+            validators.quorum_threshold()
+        )));
+    }
+    Ok(())
+}
+
+// Sums the voting power backing `certificate`: only `Precommit` votes, from distinct validators
+// of `validators`, matching the certificate's `(height, round, block_hash)` count. Anything else
+// (a different vote type, a stale or mismatched vote, a double-counted voter) is ignored rather
+// than counted towards quorum, since a certificate is a finality proof and must not be forgeable
+// out of e.g. a quorum of mere `Prevote`s.
+fn certificate_voting_power(certificate: &CommitCertificate, validators: &ValidatorSet) -> u64 {
+    let mut counted_voters = HashMap::new();
+    let mut power = 0_u64;
+    for precommit in &certificate.precommits {
+        if precommit.vote_type != VoteType::Precommit
+            || precommit.height != certificate.height.0
+            || precommit.round != certificate.round
+            || precommit.block_hash != Some(certificate.block_hash)
+        {
+            continue;
+        }
+        if counted_voters.insert(precommit.voter, ()).is_some() {
+            continue;
+        }
+        if let Some(validator) =
+            validators.validators().iter().find(|validator| validator.id == precommit.voter)
+        {
+            power += validator.voting_power;
+        }
+    }
+    power
+}
+
+// Fetches and verifies the missing blocks in `[current_height, target_height)` concurrently
+// through a `FuturesUnordered` pipeline bounded by `sync_config.lookahead`, committing them in
+// order via `ConsensusContext::decision_reached` as they become available. This reconstructs the
+// decisions for heights consensus skipped, rather than silently jumping the height counter.
+async fn catch_up<BlockT, ContextT>(
+    context: &mut ContextT,
+    current_height: BlockNumber,
+    target_height: BlockNumber,
+    sync_config: &SyncConfig,
+) -> Result<(), ConsensusError>
+where
+    BlockT: ConsensusBlock,
+    ContextT: ConsensusContext<Block = BlockT> + Clone,
+{
+    info!(
+        "Falling behind by more than the sync threshold, catching up from {} to {}.",
+        current_height, target_height
+    );
+
+    let mut pending = FuturesUnordered::new();
+    let mut next_to_fetch = current_height;
+    let mut next_to_commit = current_height;
+    let mut ready: BTreeMap<u64, Decision<BlockT>> = BTreeMap::new();
+
+    while next_to_commit < target_height {
+        // Keep at most `lookahead` blocks in flight or queued for commit; once full we pause
+        // intake and just wait for the pipeline to drain, rather than fetching unboundedly far
+        // ahead.
+        while pending.len() + ready.len() < sync_config.lookahead && next_to_fetch < target_height
+        {
+            let height = next_to_fetch;
+            let mut task_context = context.clone();
+            let fetch_timeout = sync_config.fetch_timeout;
+            pending
+                .push(async move { fetch_with_retry(&mut task_context, height, fetch_timeout).await });
+            next_to_fetch = next_to_fetch.unchecked_next();
+        }
+
+        let (height, decision) = pending
+            .next()
+            .await
+            .expect("the pipeline always has a pending fetch while below the target height")?;
+        ready.insert(height.0, decision);
+
+        // Commit everything we can in order; completions that arrive out of order simply wait in
+        // `ready` until the heights before them have committed.
+        while let Some(decision) = ready.remove(&next_to_commit.0) {
+            context.decision_reached(decision.block, decision.precommits).await?;
+            metrics::increment_counter!(PAPYRUS_CONSENSUS_SYNC_COUNT);
+            next_to_commit = next_to_commit.unchecked_next();
+        }
+    }
+
+    Ok(())
+}
+
+// Fetches and verifies the block at `height`, retrying indefinitely (with `timeout` between
+// attempts) until it succeeds. The sync pipeline has nothing better to fall back on: the block
+// must exist, so a fetch failure or timeout is assumed to be transient.
+async fn fetch_with_retry<BlockT, ContextT>(
+    context: &mut ContextT,
+    height: BlockNumber,
+    timeout: Duration,
+) -> Result<(BlockNumber, Decision<BlockT>), ConsensusError>
+where
+    BlockT: ConsensusBlock,
+    ContextT: ConsensusContext<Block = BlockT>,
+{
+    loop {
+        match tokio::time::timeout(timeout, context.fetch_and_verify_block(height)).await {
+            Ok(Ok(decision)) => return Ok((height, decision)),
+            Ok(Err(e)) => {
+                debug!("Failed to fetch/verify block at height {}: {:?}. Retrying.", height, e);
+                // Without a backoff here, a fetch that fails quickly (rather than timing out)
+                // would retry in a tight loop against the network, the exact kind of
+                // self-inflicted flood the rest of this backlog tries to avoid.
+                tokio::time::sleep(timeout).await;
+            }
+            Err(_) => {
+                debug!("Timed out fetching/verifying block at height {}. Retrying.", height);
+            }
+        }
+    }
+}
+
 async fn create_task_handler(task: ShcTask) -> ShcTask {
     tokio::time::sleep(task.duration).await;
     task
@@ -0,0 +1,354 @@
+use std::time::Duration;
+
+use futures::stream;
+use papyrus_protobuf::consensus::{ConsensusMessage, Vote, VoteType};
+use starknet_api::block::BlockNumber;
+use tokio::sync::watch;
+
+use super::{
+    build_commit_certificate,
+    certificate_voting_power,
+    wait_until_ready,
+    ConsensusWal,
+    MessageCacheConfig,
+    MultiHeightManager,
+    PeerReadinessConfig,
+    ValidatorSet,
+    WeightedValidator,
+};
+use crate::config::TimeoutsConfig;
+use crate::types::ValidatorId;
+
+fn test_wal() -> ConsensusWal {
+    // Leak the backing temp dir so it outlives the storage handles for the duration of the test.
+    let ((storage_reader, storage_writer), temp_dir) = papyrus_storage::test_utils::get_test_storage();
+    std::mem::forget(temp_dir);
+    ConsensusWal::new(storage_reader, storage_writer)
+}
+
+fn vote_message(height: u64, voter: ValidatorId) -> ConsensusMessage {
+    ConsensusMessage::Vote(Vote {
+        vote_type: VoteType::Prevote,
+        height,
+        round: 0,
+        block_hash: None,
+        voter,
+    })
+}
+
+fn manager_with_config(cache_config: MessageCacheConfig) -> MultiHeightManager {
+    MultiHeightManager::new(ValidatorId::default(), TimeoutsConfig::default(), cache_config, test_wal())
+}
+
+#[test]
+fn messages_beyond_lookahead_are_dropped() {
+    let mut manager =
+        manager_with_config(MessageCacheConfig { lookahead: 2, ..Default::default() });
+    let height = BlockNumber(10);
+    let voter = ValidatorId::default();
+
+    manager.cache_message(height, vote_message(11, voter), None);
+    manager.cache_message(height, vote_message(12, voter), None);
+    // Beyond the lookahead window, should be dropped.
+    manager.cache_message(height, vote_message(13, voter), None);
+
+    assert_eq!(manager.cached_messages_len, 2);
+}
+
+#[test]
+fn global_cache_capacity_is_enforced() {
+    let mut manager =
+        manager_with_config(MessageCacheConfig { lookahead: 100, cache_capacity: 3, ..Default::default() });
+    let height = BlockNumber(10);
+
+    for i in 0..10 {
+        let voter = ValidatorId::from(i);
+        manager.cache_message(height, vote_message(11, voter), None);
+    }
+
+    assert_eq!(manager.cached_messages_len, 3);
+    // Messages dropped for exceeding the global capacity must not leave a bookkeeping entry
+    // behind for their (attacker-controlled) sender; otherwise varying the sender on every
+    // message once the cache is full grows `cached_messages_per_sender` without bound.
+    assert_eq!(manager.cached_messages_per_sender.len(), 3);
+}
+
+#[test]
+fn per_sender_quota_is_enforced() {
+    let mut manager = manager_with_config(MessageCacheConfig {
+        lookahead: 100,
+        cache_capacity: 1000,
+        sender_quota: 2,
+    });
+    let height = BlockNumber(10);
+    let flooding_voter = ValidatorId::default();
+    let other_voter = ValidatorId::from(1_u32);
+
+    for round in 0..10 {
+        let mut message = vote_message(11, flooding_voter);
+        if let ConsensusMessage::Vote(vote) = &mut message {
+            vote.round = round;
+        }
+        manager.cache_message(height, message, None);
+    }
+    manager.cache_message(height, vote_message(11, other_voter), None);
+
+    // The flooding voter is capped at its quota, but the well-behaved voter still gets in.
+    assert_eq!(manager.cached_messages_len, 3);
+}
+
+#[tokio::test]
+async fn processed_messages_release_their_cache_slots() {
+    let mut manager =
+        manager_with_config(MessageCacheConfig { lookahead: 100, ..Default::default() });
+    let height = BlockNumber(10);
+    let voter = ValidatorId::default();
+
+    manager.cache_message(height, vote_message(11, voter), None);
+    assert_eq!(manager.cached_messages_len, 1);
+
+    let messages = manager.get_current_height_messages(BlockNumber(11)).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(manager.cached_messages_len, 0);
+    assert!(manager.cached_messages_per_sender.is_empty());
+}
+
+#[tokio::test]
+async fn wal_refuses_to_append_a_conflicting_value_for_the_same_round_and_step() {
+    let wal = test_wal();
+    let hash_a = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(1_u8));
+    let hash_b = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(2_u8));
+
+    wal.append(super::WalEntry {
+        height: 10,
+        round: 0,
+        step: super::ConsensusStep::Precommit,
+        value: Some(hash_a),
+    })
+    .await
+    .unwrap();
+
+    let conflict = wal
+        .append(super::WalEntry {
+            height: 10,
+            round: 0,
+            step: super::ConsensusStep::Precommit,
+            value: Some(hash_b),
+        })
+        .await;
+    assert!(conflict.is_err());
+}
+
+#[tokio::test]
+async fn wal_clone_handed_to_a_fresh_single_height_consensus_still_refuses_equivocation_after_a_simulated_restart()
+{
+    // `run_height` (manager.rs) hands `self.wal.clone()` to a freshly constructed
+    // `SingleHeightConsensus` on every call, including height re-entry after a crash, so that the
+    // fresh instance replays prior entries and refuses to sign a conflicting value. A full
+    // integration test driving that through `MultiHeightManager::run_height` would require a mock
+    // `ConsensusContext`/`SingleHeightConsensus`, whose trait and type aren't defined anywhere in
+    // this crate snapshot; this test instead exercises the actual mechanism `run_height` relies
+    // on: a clone of the `wal` field, handed to a "fresh" consumer simulating a post-restart
+    // `SingleHeightConsensus`, sees everything recorded before the simulated crash and still
+    // enforces the no-equivocation invariant.
+    let wal = test_wal();
+    let hash_a = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(1_u8));
+    let hash_b = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(2_u8));
+
+    // Before the simulated crash: sign a precommit for round 0.
+    wal.append(super::WalEntry {
+        height: 10,
+        round: 0,
+        step: super::ConsensusStep::Precommit,
+        value: Some(hash_a),
+    })
+    .await
+    .unwrap();
+
+    // Simulate a crash and height re-entry: drop the original handle and construct a "fresh"
+    // one the same way `run_height` does (`self.wal.clone()`), as if passed into a brand new
+    // `SingleHeightConsensus`.
+    let post_restart_wal = wal.clone();
+    drop(wal);
+
+    // The replayed state is visible to the fresh instance...
+    let replayed = post_restart_wal.entries_for_height(10).unwrap();
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(replayed[0].value, Some(hash_a));
+
+    // ...and it refuses to sign a different value for the same (height, round, step), exactly the
+    // equivocation the WAL exists to prevent.
+    let conflict = post_restart_wal
+        .append(super::WalEntry {
+            height: 10,
+            round: 0,
+            step: super::ConsensusStep::Precommit,
+            value: Some(hash_b),
+        })
+        .await;
+    assert!(conflict.is_err());
+
+    // But re-signing the same value (e.g. re-broadcasting after the restart) is still fine.
+    post_restart_wal
+        .append(super::WalEntry {
+            height: 10,
+            round: 0,
+            step: super::ConsensusStep::Precommit,
+            value: Some(hash_a),
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn wal_prune_below_drops_earlier_heights() {
+    let wal = test_wal();
+    wal.append(super::WalEntry {
+        height: 10,
+        round: 0,
+        step: super::ConsensusStep::Precommit,
+        value: None,
+    })
+    .await
+    .unwrap();
+
+    wal.prune_below(11).await.unwrap();
+
+    assert!(wal.entries_for_height(10).unwrap().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn readiness_waits_for_debounce_duration() {
+    let config = PeerReadinessConfig { min_connected_peers: 3, time_until_ready: Duration::from_secs(10) };
+    let mut peer_counts = stream::iter(vec![3_usize]).chain(stream::pending());
+    let (sender, mut receiver) = watch::channel(false);
+
+    let wait = tokio::spawn(async move {
+        wait_until_ready(&config, &mut peer_counts, &sender).await.unwrap();
+    });
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert!(!*receiver.borrow());
+
+    tokio::time::advance(Duration::from_secs(6)).await;
+    wait.await.unwrap();
+    assert!(*receiver.borrow());
+}
+
+#[tokio::test(start_paused = true)]
+async fn readiness_debounce_resets_if_peers_drop_below_threshold() {
+    let config = PeerReadinessConfig { min_connected_peers: 3, time_until_ready: Duration::from_secs(10) };
+    // Drops below the threshold mid-debounce, then climbs back and holds for the full duration.
+    let mut peer_counts = stream::iter(vec![3_usize, 1, 3]).chain(stream::pending());
+    let (sender, receiver) = watch::channel(false);
+
+    let wait = tokio::spawn(async move {
+        wait_until_ready(&config, &mut peer_counts, &sender).await.unwrap();
+    });
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    tokio::time::advance(Duration::from_secs(11)).await;
+    wait.await.unwrap();
+    assert!(*receiver.borrow());
+}
+
+fn weighted(id: u32, voting_power: u64) -> WeightedValidator {
+    WeightedValidator { id: ValidatorId::from(id), voting_power }
+}
+
+#[test]
+fn quorum_threshold_is_more_than_two_thirds_of_total_power_under_skew() {
+    // One whale with 90% of the power and 9 equally-weighted minnows sharing the rest.
+    let mut validators = vec![weighted(0, 90)];
+    validators.extend((1..=9).map(|id| weighted(id, 1)));
+    let validator_set = ValidatorSet::new(validators);
+
+    assert_eq!(validator_set.total_power(), 99);
+    // 2/3 of 99 is 66, so quorum is the first value strictly greater than that: 67.
+    assert_eq!(validator_set.quorum_threshold(), 67);
+    assert!(!validator_set.has_quorum(66));
+    assert!(validator_set.has_quorum(67));
+    // The whale alone reaches quorum: its 90 is more than 2/3 of the 99 total, however many
+    // validators share the rest.
+    assert!(validator_set.has_quorum(90));
+    assert!(validator_set.has_quorum(91));
+}
+
+#[test]
+fn proposer_rotation_favors_higher_voting_power() {
+    // A validator with 3x the power of the others should propose roughly 3x as often over many
+    // rounds, and every validator must get a turn.
+    let validator_set =
+        ValidatorSet::new(vec![weighted(0, 3), weighted(1, 1), weighted(2, 1), weighted(3, 1)]);
+
+    let mut proposal_counts = std::collections::HashMap::new();
+    for round in 0..600 {
+        let proposer = validator_set.proposer_for_round(round);
+        *proposal_counts.entry(proposer).or_insert(0_u32) += 1;
+    }
+
+    assert_eq!(proposal_counts.len(), 4, "every validator must propose at least once");
+    let heavy_count = proposal_counts[&ValidatorId::from(0_u32)];
+    // With total power 6 and the heavy validator holding 3, it should propose half the time.
+    assert_eq!(heavy_count, 300);
+}
+
+#[test]
+fn proposer_rotation_is_deterministic_given_the_same_round() {
+    let validator_set = ValidatorSet::new(vec![weighted(0, 5), weighted(1, 5)]);
+    assert_eq!(validator_set.proposer_for_round(7), validator_set.proposer_for_round(7));
+}
+
+#[test]
+fn commit_certificate_takes_its_round_from_the_precommits() {
+    let block_hash = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(7_u8));
+    let height = BlockNumber(10);
+    let precommits = vec![
+        Vote {
+            vote_type: VoteType::Precommit,
+            height: height.0,
+            round: 2,
+            block_hash: Some(block_hash),
+            voter: ValidatorId::default(),
+        },
+        Vote {
+            vote_type: VoteType::Precommit,
+            height: height.0,
+            round: 2,
+            block_hash: Some(block_hash),
+            voter: ValidatorId::from(1_u32),
+        },
+    ];
+
+    let certificate = build_commit_certificate(height, block_hash, &precommits);
+
+    assert_eq!(certificate.height, height);
+    assert_eq!(certificate.round, 2);
+    assert_eq!(certificate.block_hash, block_hash);
+    assert_eq!(certificate.precommits, precommits);
+}
+
+#[test]
+fn certificate_built_from_prevotes_carries_no_voting_power() {
+    // A quorum of `Prevote`s matching `(height, round, block_hash)` must not count towards a
+    // certificate's quorum; only `Precommit`s constitute a finality proof.
+    let block_hash = starknet_api::block::BlockHash(starknet_api::hash::StarkFelt::from(9_u8));
+    let height = BlockNumber(10);
+    let validator_set =
+        ValidatorSet::new(vec![weighted(0, 1), weighted(1, 1), weighted(2, 1), weighted(3, 1)]);
+    let prevotes: Vec<_> = (0..4)
+        .map(|id| Vote {
+            vote_type: VoteType::Prevote,
+            height: height.0,
+            round: 0,
+            block_hash: Some(block_hash),
+            voter: ValidatorId::from(id),
+        })
+        .collect();
+    let certificate = build_commit_certificate(height, block_hash, &prevotes);
+
+    let power = certificate_voting_power(&certificate, &validator_set);
+
+    assert_eq!(power, 0);
+    assert!(!validator_set.has_quorum(power));
+}